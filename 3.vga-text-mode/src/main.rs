@@ -9,6 +9,11 @@
 
 #![no_std]
 #![no_main]
+// 標準のテストハーネスはstd::panic::catch_unwindに依存していてno_stdでは使えないため、
+// custom_test_frameworksを使って自前のtest_runnerに差し替えます。
+#![feature(custom_test_frameworks)]
+#![test_runner(crate::test_runner)]
+#![reexport_test_harness_main = "test_main"]
 
 use core::panic::PanicInfo;
 
@@ -19,12 +24,24 @@ use core::panic::PanicInfo;
     no_std 環境では私達の手でそれを実装する必要があります。
     ！はnever型という意味
 */
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
     println!("{}", info);
     loop {}
 }
 
+// テスト実行中のパニックはVGAが見えないCI環境で起きるため、シリアル経由で[failed]を報告してから
+// exit_qemu(Failed)でQEMUを終了させます。
+#[cfg(test)]
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    serial_println!("[failed]\n");
+    serial_println!("エラー: {}\n", info);
+    exit_qemu(QemuExitCode::Failed);
+    loop {}
+}
+
 
 /**
   <エントリポイントの上書き>
@@ -38,15 +55,71 @@ fn panic(info: &PanicInfo) -> ! {
     _startという名前をつける理由は、これがほとんどのシステムのデフォルトのエントリポイント名だからです。
 */
 #[no_mangle]
-pub extern "C" fn _start() {
+pub extern "C" fn _start() -> ! {
     println!("Hello World{}", "!");
-    panic!("Some panic message");
+
+    // cargo testでビルドされたときだけ、custom_test_frameworksが生成するtest_mainを呼び出します。
+    #[cfg(test)]
+    test_main();
+
     loop {}
 }
 
 static HELLO: &[u8] = b"Hello World!";
 
 mod vga_buffer;
+mod serial;
 
+// QEMUのisa-debug-exitデバイス(ポート0xf4)へ書き込む終了コードです。
+// 実際のexit codeは (value << 1) | 1 になるため、0x10/0x11を選んでいます。
+#[cfg(test)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub enum QemuExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
 
+#[cfg(test)]
+pub fn exit_qemu(exit_code: QemuExitCode) {
+    use x86_64::instructions::port::Port;
 
+    unsafe {
+        let mut port = Port::new(0xf4);
+        port.write(exit_code as u32);
+    }
+}
+
+// テスト関数をまとめて実行するトレイトです。実行前後に関数名と[ok]をシリアルへ出力します。
+#[cfg(test)]
+pub trait Testable {
+    fn run(&self);
+}
+
+#[cfg(test)]
+impl<T> Testable for T
+where
+    T: Fn(),
+{
+    fn run(&self) {
+        serial_print!("{}...\t", core::any::type_name::<T>());
+        self();
+        serial_println!("[ok]");
+    }
+}
+
+// custom_test_frameworksが生成するtest_mainから呼ばれるテストランナーです。
+// 全テストが通ったらQEMUをSuccessコードで終了させます(1つでもパニックすればpanicハンドラがFailedで終了)。
+#[cfg(test)]
+fn test_runner(tests: &[&dyn Testable]) {
+    serial_println!("Running {} tests", tests.len());
+    for test in tests {
+        test.run();
+    }
+    exit_qemu(QemuExitCode::Success);
+}
+
+#[test_case]
+fn trivial_assertion() {
+    assert_eq!(1, 1);
+}
@@ -0,0 +1,104 @@
+use core::fmt;
+
+use lazy_static::lazy_static;
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+// 16550 UARTの各レジスタは、ベースポート(COM1なら0x3F8)からのオフセットとして並んでいます。
+// ここでは外部クレートに頼らず、データシート通りにオフセットへ直接読み書きします。
+struct SerialPort {
+    data: Port<u8>,
+    interrupt_enable: Port<u8>,
+    fifo_control: Port<u8>,
+    line_control: Port<u8>,
+    modem_control: Port<u8>,
+    line_status: Port<u8>,
+}
+
+impl SerialPort {
+    const fn new(base: u16) -> SerialPort {
+        SerialPort {
+            data: Port::new(base),
+            interrupt_enable: Port::new(base + 1),
+            fifo_control: Port::new(base + 2),
+            line_control: Port::new(base + 3),
+            modem_control: Port::new(base + 4),
+            line_status: Port::new(base + 5),
+        }
+    }
+
+    // ボーレート除数・データビット・FIFOを設定してポートを使える状態にします。
+    unsafe fn init(&mut self) {
+        // 割り込みを無効化
+        self.interrupt_enable.write(0x00);
+        // DLAB(Divisor Latch Access Bit)を立て、データ/割り込みイネーブルレジスタを
+        // ボーレート除数の下位/上位バイトとして使えるようにする
+        self.line_control.write(0x80);
+        // 除数を3に設定(38400ボー)
+        self.data.write(0x03);
+        self.interrupt_enable.write(0x00);
+        // DLABを下げ、8bit・パリティなし・ストップビット1(8N1)を設定
+        self.line_control.write(0x03);
+        // FIFOを有効化・クリアし、14バイトのしきい値を設定
+        self.fifo_control.write(0xC7);
+        // IRQ有効化(OUT2)、DTR/RTSをセットしてループバックを解除
+        self.modem_control.write(0x0B);
+    }
+
+    fn line_status(&mut self) -> u8 {
+        unsafe { self.line_status.read() }
+    }
+
+    // 送信バッファが空になる(ラインステータスのbit5が立つ)までビジーウェイトしてから1バイト送ります。
+    fn send(&mut self, byte: u8) {
+        while self.line_status() & 0x20 == 0 {}
+        unsafe {
+            self.data.write(byte);
+        }
+    }
+}
+
+impl fmt::Write for SerialPort {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
+
+lazy_static! {
+    // COM1(0x3F8)を初期化実行時に遅延初期化する、WRITERと同じパターンです。
+    static ref SERIAL1: Mutex<SerialPort> = {
+        let mut serial_port = SerialPort::new(0x3F8);
+        unsafe {
+            serial_port.init();
+        }
+        Mutex::new(serial_port)
+    };
+}
+
+#[doc(hidden)]
+pub fn _print(args: fmt::Arguments) {
+    use core::fmt::Write;
+    SERIAL1
+        .lock()
+        .write_fmt(args)
+        .expect("シリアルポートへの書き込みに失敗しました");
+}
+
+// シリアルポート(QEMUの標準出力にリダイレクトされる)へ出力するマクロです。
+// VGAバッファは人間の目にしか見えないため、CIやcargo testから中身を確認するために使います。
+#[macro_export]
+macro_rules! serial_print {
+    ($($arg:tt)*) => {
+        $crate::serial::_print(format_args!($($arg)*));
+    };
+}
+
+#[macro_export]
+macro_rules! serial_println {
+    () => ($crate::serial_print!("\n"));
+    ($fmt:expr) => ($crate::serial_print!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => ($crate::serial_print!(concat!($fmt, "\n"), $($arg)*));
+}
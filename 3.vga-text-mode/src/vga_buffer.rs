@@ -32,15 +32,26 @@ pub enum Color {
 // ColorCodeがu8と全く同じデータ構造を持つようにするために、
 // repr(transparent)属性（訳注：翻訳当時、リンク先未訳）を使います。
 // derive:継承の様なもの、継承している内容はこれが参考になった。https://qiita.com/apollo_program/items/2495dda519ae160971ed
+//
+// VGAの属性バイトは実際には 下位4bit:前景色 / 上位3bit:背景色 / 最上位1bit:点滅 という構成になっています。
+// 以前の実装ではbackgroundをそのまま4bit左シフトしていたため、8以上の値を渡すと
+// 点滅ビット(bit7)を踏んでしまい、かつ点滅を要求する手段がありませんでした。
+// with_blinkでbit単位の構成を明示的に扱えるようにします。
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(transparent)]
-struct ColorCode(u8);
+pub struct ColorCode(u8);
 
 impl ColorCode {
-    fn new(background: Color, foreground: Color) -> ColorCode {
-        // 左シフトとOR演算 例えばbackgroundがBlack:0なら
-        // 2進数で 00000000(シフト演算後) 、foregroundがYellow:14なら00001110、OR演算して00001110、
-        ColorCode((background as u8) << 4 | (foreground as u8))
+    // 点滅なしの従来通りのコンストラクタ。with_blinkの薄いラッパーとして残します。
+    pub fn new(background: Color, foreground: Color) -> ColorCode {
+        ColorCode::with_blink(foreground, background, false)
+    }
+
+    // 背景色は下位3bitにマスクして点滅ビット(bit7)と衝突しないようにし、
+    // blinkがtrueならbit7を立てます。
+    pub fn with_blink(foreground: Color, background: Color, blink: bool) -> ColorCode {
+        let blink_bit = if blink { 0b1000_0000 } else { 0 };
+        ColorCode(blink_bit | (background as u8 & 0b0111) << 4 | (foreground as u8))
     }
 }
 
@@ -61,12 +72,34 @@ struct Buffer {
     chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
 }
 
+// ANSI CSIシーケンス(ESC [ ... m)を読んでいる途中の状態です。
+// ESC [ 31 ; 1 m のように、パラメータはセミコロン区切りの数字が0個以上続き、最後に最終バイトが来ます。
+enum AnsiState {
+    // 通常の文字として出力する
+    Ground,
+    // ESC(0x1b)は受け取ったが、続く'['はまだ来ていない
+    Escape,
+    // '['まで来ていて、パラメータを集めている
+    Csi,
+}
+
+// CSIシーケンス中に集めるパラメータの最大個数です。
+// "\x1b[31;1;5m"のように複数のSGRパラメータを1つのシーケンスにまとめるケースを想定し、
+// それを超える分は読み捨てます(シーケンス自体は最後まで消費します)。
+const MAX_CSI_PARAMS: usize = 4;
+
 // 'staticライフタイムは、
 // その参照がプログラムの実行中ずっと有効であることを指定しています
 pub struct Writer {
     column_position: usize,
     color_code: ColorCode,
+    // reset_color(SGRの0)で戻す先になる、起動時の色設定です。
+    default_color_code: ColorCode,
     buffer: &'static mut Buffer,
+    ansi_state: AnsiState,
+    csi_params: [u16; MAX_CSI_PARAMS],
+    csi_param_count: usize,
+    csi_current: Option<u16>,
 }
 
 impl Writer {
@@ -89,23 +122,115 @@ impl Writer {
 
 
                 self.column_position += 1;
+                update_hardware_cursor(row, self.column_position);
             }
         }
     }
 
     pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                // 出力可能なASCIIバイトか、改行コード
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
-
-                // 出力可能なASCIIバイトではない
-                // 文字■を出力します（これはVGAハードウェアにおいて16進コード0xfeを持っています）。
-                _ => self.write_byte(0xfe),
+        // VGAのテキストバッファはASCIIではなくCode Page 437のグリフセットを使っています。
+        // バイト単位で捨てるのではなく、charごとにCP437_TABLEを引いて変換することで、
+        // アクセント付き文字や罫線素片・網掛けといったCP437側に存在するグリフを活かします。
+        // さらにANSIのSGRエスケープシーケンス(\x1b[...m)を読み取り、文字を出力する前に
+        // color_codeを更新することで、1回のprintln!の中で複数の色を扱えるようにします。
+        for c in s.chars() {
+            match self.ansi_state {
+                AnsiState::Ground => self.write_ground_char(c),
+                AnsiState::Escape => {
+                    if c == '[' {
+                        self.csi_param_count = 0;
+                        self.csi_current = None;
+                        self.ansi_state = AnsiState::Csi;
+                    } else {
+                        // ESCの次が'['でなければCSIシーケンスではないので、
+                        // ESC自体は読み捨てつつ、この文字は通常通り出力する
+                        self.ansi_state = AnsiState::Ground;
+                        self.write_ground_char(c);
+                    }
+                }
+                AnsiState::Csi => self.handle_csi_byte(c),
+            }
+        }
+    }
+
+    // Ground状態での1文字分の出力です。ESC(0x1b)はCSIシーケンスの開始として状態遷移だけ行います。
+    fn write_ground_char(&mut self, c: char) {
+        let code_point = c as u32;
+        match code_point {
+            0x1b => self.ansi_state = AnsiState::Escape,
+            0x20..=0x7e => self.write_byte(code_point as u8),
+            _ if c == '\n' => self.write_byte(b'\n'),
+            _ => self.write_byte(cp437_byte(code_point)),
+        }
+    }
+
+    // CSIシーケンスのパラメータ部分(数字・';')と最終バイト('m')を1文字ずつ処理します。
+    fn handle_csi_byte(&mut self, c: char) {
+        match c {
+            '0'..='9' => {
+                let digit = c as u16 - '0' as u16;
+                let current = self.csi_current.unwrap_or(0);
+                self.csi_current = Some(current.saturating_mul(10).saturating_add(digit));
+            }
+            ';' => self.push_csi_param(),
+            'm' => {
+                self.push_csi_param();
+                self.apply_sgr_params();
+                self.ansi_state = AnsiState::Ground;
+            }
+            _ => {
+                // パラメータでも対応する最終バイトでもないものが来たら、壊れたシーケンスとして読み捨てる
+                self.ansi_state = AnsiState::Ground;
+            }
+        }
+    }
+
+    fn push_csi_param(&mut self) {
+        if self.csi_param_count < self.csi_params.len() {
+            self.csi_params[self.csi_param_count] = self.csi_current.unwrap_or(0);
+            self.csi_param_count += 1;
+        }
+        self.csi_current = None;
+    }
+
+    // 集めたSGRパラメータを順番に適用します。0はデフォルト色へのリセット、
+    // 30..=37/90..=97は前景色、40..=47/100..=107は背景色、5は点滅の有効化です。
+    fn apply_sgr_params(&mut self) {
+        if self.csi_param_count == 0 {
+            // パラメータが1つもなければリセットとして扱う。
+            // なお "\x1b[m" は'm'の時点で空のパラメータが0として積まれるため、下のループの0でリセットされる
+            self.reset_color();
+            return;
+        }
+
+        for i in 0..self.csi_param_count {
+            match self.csi_params[i] {
+                0 => self.reset_color(),
+                5 => self.set_blink(true),
+                n @ 30..=37 => self.set_foreground(ansi_color(n - 30)),
+                n @ 90..=97 => self.set_foreground(ansi_bright_color(n - 90)),
+                n @ 40..=47 => self.set_background(ansi_color(n - 40)),
+                n @ 100..=107 => self.set_background(ansi_bright_color(n - 100)),
+                _ => {}
             }
         }
     }
 
+    // 背景色・点滅ビットはそのままに、前景色(下位4bit)だけを差し替えます。
+    fn set_foreground(&mut self, color: Color) {
+        self.color_code = ColorCode(self.color_code.0 & 0b1111_0000 | (color as u8 & 0b0000_1111));
+    }
+
+    // 前景色・点滅ビットはそのままに、背景色(bit4..=6)だけを差し替えます。
+    fn set_background(&mut self, color: Color) {
+        self.color_code = ColorCode(self.color_code.0 & 0b1000_1111 | (color as u8 & 0b0111) << 4);
+    }
+
+    // 起動時の色設定に戻します。
+    fn reset_color(&mut self) {
+        self.color_code = self.default_color_code;
+    }
+
     fn new_line(&mut self) {
         for row in 1..BUFFER_HEIGHT {
             for col in 0..BUFFER_WIDTH {
@@ -115,6 +240,38 @@ impl Writer {
         }
         self.clear_row(BUFFER_HEIGHT - 1);
         self.column_position = 0;
+        update_hardware_cursor(BUFFER_HEIGHT - 1, 0);
+    }
+
+    // ハードウェアカーソルを任意の位置へ動かします。対話的なプロンプトなどで使う想定です。
+    // 現時点ではクレート内から呼ばれていない公開APIなので、Color enumと同様にdead_codeを抑制します。
+    #[allow(dead_code)]
+    pub fn set_cursor(&mut self, row: usize, col: usize) {
+        update_hardware_cursor(row, col);
+    }
+
+    // 全行を空白で埋め、書き込み位置とカーソルを画面左上へ戻します。
+    // こちらも同様に呼び出し元がまだないため、dead_codeを抑制しておきます。
+    #[allow(dead_code)]
+    pub fn clear_screen(&mut self) {
+        for row in 0..BUFFER_HEIGHT {
+            self.clear_row(row);
+        }
+        self.column_position = 0;
+        update_hardware_cursor(0, 0);
+    }
+
+    // 以降の書き込みに使われる属性バイトを丸ごと差し替えます。
+    // 現時点ではクレート内から呼ばれていない公開APIなので、Color enumと同様にdead_codeを抑制します。
+    #[allow(dead_code)]
+    pub fn set_color(&mut self, color_code: ColorCode) {
+        self.color_code = color_code;
+    }
+
+    // 前景色・背景色は保持したまま、点滅ビット(bit7)だけを切り替えます。
+    pub fn set_blink(&mut self, blink: bool) {
+        let blink_bit = if blink { 0b1000_0000 } else { 0 };
+        self.color_code = ColorCode(self.color_code.0 & 0b0111_1111 | blink_bit);
     }
 
     // このメソッドはすべての文字を空白文字で書き換えることによって行をクリアしてくれます。
@@ -129,6 +286,154 @@ impl Writer {
     }
 }
 
+// Unicodeのスカラ値からCP437のコードポイント(0x00..=0x1f, 0x80..=0xff)への変換テーブルです。
+// code_pointの昇順にソートしてあり、binary_search_by_keyで引けるようにしています。
+// no_stdかつヒープを使わずに済むように、&'staticなスライスのまま持っています。
+const CP437_TABLE: &[(u32, u8)] = &[
+    (0x00A0, 0xFF), (0x00A1, 0xAD), (0x00A2, 0x9B), (0x00A3, 0x9C),
+    (0x00A5, 0x9D), (0x00A7, 0x15), (0x00AA, 0xA6), (0x00AB, 0xAE),
+    (0x00AC, 0xAA), (0x00B0, 0xF8), (0x00B1, 0xF1), (0x00B2, 0xFD),
+    (0x00B5, 0xE6), (0x00B6, 0x14), (0x00B7, 0xFA), (0x00BA, 0xA7),
+    (0x00BB, 0xAF), (0x00BC, 0xAC), (0x00BD, 0xAB), (0x00BF, 0xA8),
+    (0x00C4, 0x8E), (0x00C5, 0x8F), (0x00C6, 0x92), (0x00C7, 0x80),
+    (0x00C9, 0x90), (0x00D1, 0xA5), (0x00D6, 0x99), (0x00DC, 0x9A),
+    (0x00DF, 0xE1), (0x00E0, 0x85), (0x00E1, 0xA0), (0x00E2, 0x83),
+    (0x00E4, 0x84), (0x00E5, 0x86), (0x00E6, 0x91), (0x00E7, 0x87),
+    (0x00E8, 0x8A), (0x00E9, 0x82), (0x00EA, 0x88), (0x00EB, 0x89),
+    (0x00EC, 0x8D), (0x00ED, 0xA1), (0x00EE, 0x8C), (0x00EF, 0x8B),
+    (0x00F1, 0xA4), (0x00F2, 0x95), (0x00F3, 0xA2), (0x00F4, 0x93),
+    (0x00F6, 0x94), (0x00F7, 0xF6), (0x00F9, 0x97), (0x00FA, 0xA3),
+    (0x00FB, 0x96), (0x00FC, 0x81), (0x00FF, 0x98), (0x0192, 0x9F),
+    (0x0393, 0xE2), (0x0398, 0xE9), (0x03A3, 0xE4), (0x03A6, 0xE8),
+    (0x03A9, 0xEA), (0x03B1, 0xE0), (0x03B4, 0xEB), (0x03B5, 0xEE),
+    (0x03C0, 0xE3), (0x03C3, 0xE5), (0x03C4, 0xE7), (0x03C6, 0xED),
+    (0x2022, 0x07), (0x203C, 0x13), (0x207F, 0xFC), (0x20A7, 0x9E),
+    (0x2190, 0x1B), (0x2191, 0x18), (0x2192, 0x1A), (0x2193, 0x19),
+    (0x2194, 0x1D), (0x2195, 0x12), (0x21A8, 0x17), (0x2219, 0xF9),
+    (0x221A, 0xFB), (0x221E, 0xEC), (0x221F, 0x1C), (0x2229, 0xEF),
+    (0x2248, 0xF7), (0x2261, 0xF0), (0x2264, 0xF3), (0x2265, 0xF2),
+    (0x2310, 0xA9), (0x2320, 0xF4), (0x2321, 0xF5), (0x2500, 0xC4),
+    (0x2502, 0xB3), (0x250C, 0xDA), (0x2510, 0xBF), (0x2514, 0xC0),
+    (0x2518, 0xD9), (0x251C, 0xC3), (0x2524, 0xB4), (0x252C, 0xC2),
+    (0x2534, 0xC1), (0x253C, 0xC5), (0x2550, 0xCD), (0x2551, 0xBA),
+    (0x2552, 0xD5), (0x2553, 0xD6), (0x2554, 0xC9), (0x2555, 0xB8),
+    (0x2556, 0xB7), (0x2557, 0xBB), (0x2558, 0xD4), (0x2559, 0xD3),
+    (0x255A, 0xC8), (0x255B, 0xBE), (0x255C, 0xBD), (0x255D, 0xBC),
+    (0x255E, 0xC6), (0x255F, 0xC7), (0x2560, 0xCC), (0x2561, 0xB5),
+    (0x2562, 0xB6), (0x2563, 0xB9), (0x2564, 0xD1), (0x2565, 0xD2),
+    (0x2566, 0xCB), (0x2567, 0xCF), (0x2568, 0xD0), (0x2569, 0xCA),
+    (0x256A, 0xD8), (0x256B, 0xD7), (0x256C, 0xCE), (0x2580, 0xDF),
+    (0x2584, 0xDC), (0x2588, 0xDB), (0x258C, 0xDD), (0x2590, 0xDE),
+    (0x2591, 0xB0), (0x2592, 0xB1), (0x2593, 0xB2), (0x25A0, 0xFE),
+    (0x25AC, 0x16), (0x25B2, 0x1E), (0x25BA, 0x10), (0x25BC, 0x1F),
+    (0x25C4, 0x11), (0x25CB, 0x09), (0x25D8, 0x08), (0x25D9, 0x0A),
+    (0x263A, 0x01), (0x263B, 0x02), (0x263C, 0x0F), (0x2640, 0x0C),
+    (0x2642, 0x0B), (0x2660, 0x06), (0x2663, 0x05), (0x2665, 0x03),
+    (0x2666, 0x04), (0x266A, 0x0D), (0x266B, 0x0E),
+];
+
+// CP437_TABLEを引いて対応するバイトを返します。見つからなければ■(0xfe)にフォールバックします。
+fn cp437_byte(code_point: u32) -> u8 {
+    match CP437_TABLE.binary_search_by_key(&code_point, |&(cp, _)| cp) {
+        Ok(index) => CP437_TABLE[index].1,
+        Err(_) => 0xfe,
+    }
+}
+
+#[test_case]
+fn test_cp437_known_mappings() {
+    assert_eq!(cp437_byte(0x2591), 0xb0);
+    assert_eq!(cp437_byte(0x2502), 0xb3);
+    assert_eq!(cp437_byte(0x00E9), 0x82);
+}
+
+#[test_case]
+fn test_cp437_unmapped_falls_back_to_0xfe() {
+    assert_eq!(cp437_byte(0x1F600), 0xfe);
+}
+
+// CRTCの点滅カーソルを(row, col)に対応する線形位置へ動かします。
+// インデックスレジスタ(0x3D4)でカーソル位置の下位/上位バイトを選び、データレジスタ(0x3D5)へ書き込みます。
+// ポートI/OはBIOSが載っているx86_64環境でのみ意味を持つため、ターゲットをx86_64に限定しています。
+#[cfg(target_arch = "x86_64")]
+fn update_hardware_cursor(row: usize, col: usize) {
+    use x86_64::instructions::port::Port;
+
+    let position = (row * BUFFER_WIDTH + col) as u16;
+
+    unsafe {
+        let mut index_port: Port<u8> = Port::new(0x3D4);
+        let mut data_port: Port<u8> = Port::new(0x3D5);
+
+        index_port.write(0x0F);
+        data_port.write((position & 0xFF) as u8);
+        index_port.write(0x0E);
+        data_port.write((position >> 8) as u8);
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn update_hardware_cursor(_row: usize, _col: usize) {}
+
+// ANSIの標準8色(30..=37/40..=47からオフセットを引いた0..=7)をVGAのColorへ対応付けます。
+fn ansi_color(index: u16) -> Color {
+    match index {
+        0 => Color::Black,
+        1 => Color::Red,
+        2 => Color::Green,
+        3 => Color::Brown,
+        4 => Color::Blue,
+        5 => Color::Magenta,
+        6 => Color::Cyan,
+        _ => Color::LightGray,
+    }
+}
+
+// ANSIのbright版8色(90..=97/100..=107からオフセットを引いた0..=7)をVGAのColorへ対応付けます。
+fn ansi_bright_color(index: u16) -> Color {
+    match index {
+        0 => Color::DarkGray,
+        1 => Color::LightRed,
+        2 => Color::LightGreen,
+        3 => Color::Yellow,
+        4 => Color::LightBlue,
+        5 => Color::Pink,
+        6 => Color::LightCyan,
+        _ => Color::White,
+    }
+}
+
+#[test_case]
+fn test_sgr_sets_foreground_and_resets() {
+    let mut writer = WRITER.lock();
+    let default_color = writer.default_color_code;
+
+    writer.write_string("\x1b[31m");
+    assert_eq!(writer.color_code.0 & 0b0000_1111, Color::Red as u8);
+
+    writer.write_string("\x1b[0m");
+    assert_eq!(writer.color_code, default_color);
+}
+
+#[test_case]
+fn test_sgr_bare_reset_matches_explicit_zero() {
+    let mut writer = WRITER.lock();
+    let default_color = writer.default_color_code;
+
+    writer.write_string("\x1b[31m");
+    writer.write_string("\x1b[m");
+    assert_eq!(writer.color_code, default_color);
+}
+
+#[test_case]
+fn test_stray_escape_does_not_eat_next_byte() {
+    let mut writer = WRITER.lock();
+
+    writer.column_position = 0;
+    writer.write_string("\x1bH");
+    assert_eq!(writer.column_position, 1);
+}
+
 // フォーマットマクロの作成
 // 整数や浮動小数点数といった様々な型を簡単に出力できます。
 // それらをサポートするためには、core::fmt::Writeトレイトを実装する必要があります。
@@ -147,7 +452,12 @@ lazy_static! {
     pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
         column_position: 0,
         color_code: ColorCode::new(Color::Black, Color::Yellow),
+        default_color_code: ColorCode::new(Color::Black, Color::Yellow),
         buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
+        ansi_state: AnsiState::Ground,
+        csi_params: [0; MAX_CSI_PARAMS],
+        csi_param_count: 0,
+        csi_current: None,
     });
 }
 